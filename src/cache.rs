@@ -0,0 +1,289 @@
+//! Byte-bounded LRU cache for proxied playlists/segments, with single-flight
+//! de-duplication so concurrent requests for the same upstream URL don't stampede origin.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+use http::header::{HeaderMap, HeaderValue};
+use tokio::sync::Notify;
+
+/// TTL used for playlists when upstream doesn't send cache-control hints; these are expected to
+/// change frequently on live streams.
+pub const DEFAULT_PLAYLIST_TTL: Duration = Duration::from_secs(2);
+/// TTL used for segments/binary chunks when upstream doesn't send cache-control hints; these are
+/// effectively immutable once published.
+pub const DEFAULT_SEGMENT_TTL: Duration = Duration::from_secs(300);
+
+#[derive(Clone)]
+pub struct CacheEntry {
+    pub body: Bytes,
+    pub content_type: Option<HeaderValue>,
+    expires_at: Instant,
+}
+
+impl CacheEntry {
+    pub fn new(body: Bytes, content_type: Option<HeaderValue>, ttl: Duration) -> Self {
+        Self {
+            body,
+            content_type,
+            expires_at: Instant::now() + ttl,
+        }
+    }
+
+    fn is_expired(&self) -> bool {
+        Instant::now() >= self.expires_at
+    }
+}
+
+/// Returned by [`lookup`]: either the cached entry, or a guard the caller must
+/// [`FetchGuard::complete`] (or drop) once it has fetched the value itself, so that any other
+/// request waiting on the same key is released.
+pub enum Lookup {
+    Hit(CacheEntry),
+    Miss(FetchGuard),
+}
+
+pub struct FetchGuard {
+    cache: Arc<Cache>,
+    key: String,
+    done: bool,
+}
+
+impl FetchGuard {
+    /// Stores the freshly-fetched entry in the cache and wakes up any requests that were
+    /// waiting on this same key.
+    pub fn complete(mut self, entry: CacheEntry) {
+        self.cache.insert(self.key.clone(), entry);
+        self.done = true;
+    }
+}
+
+impl Drop for FetchGuard {
+    fn drop(&mut self) {
+        if !self.done {
+            // NOTE: The fetch failed or was abandoned; wake up waiters so they retry on their
+            // own instead of hanging forever.
+            self.cache.release(&self.key);
+        }
+    }
+}
+
+/// Looks up `key` in `cache`, joining any in-flight fetch for the same key instead of
+/// triggering a second one (single-flight / cache-lock behavior).
+pub async fn lookup(cache: Arc<Cache>, key: &str) -> Lookup {
+    loop {
+        if let Some(entry) = cache.get(key) {
+            return Lookup::Hit(entry);
+        }
+
+        let mut inflight = cache.inflight.lock().unwrap();
+
+        if let Some(notify) = inflight.get(key).cloned() {
+            // NOTE: The waiter must register itself with `Notify` *before* releasing the
+            // `inflight` lock. `notify_waiters()` wakes only already-registered waiters and
+            // stores no permit, so dropping the lock first leaves a window where `release()` can
+            // fire (and the entry land in the cache) before `.notified()` is ever polled, losing
+            // the wakeup and hanging the request forever.
+            let notified = notify.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+
+            drop(inflight);
+
+            notified.await;
+        } else {
+            inflight.insert(key.to_string(), Arc::new(Notify::new()));
+
+            drop(inflight);
+
+            return Lookup::Miss(FetchGuard {
+                cache,
+                key: key.to_string(),
+                done: false,
+            });
+        }
+    }
+}
+
+struct Store {
+    entries: HashMap<String, CacheEntry>,
+    order: VecDeque<String>,
+    bytes: usize,
+}
+
+impl Store {
+    fn touch(&mut self, key: &str) {
+        if let Some(position) = self.order.iter().position(|k| k == key) {
+            self.order.remove(position);
+            self.order.push_back(key.to_string());
+        }
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.bytes = self.bytes.saturating_sub(entry.body.len());
+        }
+
+        if let Some(position) = self.order.iter().position(|k| k == key) {
+            self.order.remove(position);
+        }
+    }
+
+    fn insert(&mut self, key: String, entry: CacheEntry, max_bytes: usize) {
+        self.remove(&key);
+
+        self.bytes += entry.body.len();
+        self.entries.insert(key.clone(), entry);
+        self.order.push_back(key);
+
+        while self.bytes > max_bytes {
+            let Some(oldest) = self.order.pop_front() else {
+                break;
+            };
+
+            if let Some(entry) = self.entries.remove(&oldest) {
+                self.bytes = self.bytes.saturating_sub(entry.body.len());
+            }
+        }
+    }
+}
+
+pub struct Cache {
+    max_bytes: usize,
+    store: Mutex<Store>,
+    inflight: Mutex<HashMap<String, Arc<Notify>>>,
+}
+
+impl Cache {
+    pub fn new(max_bytes: usize) -> Self {
+        Self {
+            max_bytes,
+            store: Mutex::new(Store {
+                entries: HashMap::new(),
+                order: VecDeque::new(),
+                bytes: 0,
+            }),
+            inflight: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<CacheEntry> {
+        let mut store = self.store.lock().unwrap();
+
+        match store.entries.get(key) {
+            Some(entry) if !entry.is_expired() => {
+                let entry = entry.clone();
+                store.touch(key);
+
+                Some(entry)
+            }
+            Some(_) => {
+                store.remove(key);
+
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn insert(&self, key: String, entry: CacheEntry) {
+        {
+            let mut store = self.store.lock().unwrap();
+            store.insert(key.clone(), entry, self.max_bytes);
+        }
+
+        self.release(&key);
+    }
+
+    fn release(&self, key: &str) {
+        let notify = self.inflight.lock().unwrap().remove(key);
+
+        if let Some(notify) = notify {
+            notify.notify_waiters();
+        }
+    }
+}
+
+/// Derives a cache TTL from the upstream `Cache-Control`/`Expires` headers, falling back to
+/// `default_ttl` when neither is present or parseable.
+pub fn ttl_from_headers(headers: &HeaderMap, default_ttl: Duration) -> Duration {
+    if let Some(cache_control) = headers.get(http::header::CACHE_CONTROL) {
+        if let Ok(cache_control) = cache_control.to_str() {
+            for directive in cache_control.split(',') {
+                let directive = directive.trim();
+
+                if let Some(seconds) = directive.strip_prefix("max-age=") {
+                    if let Ok(seconds) = seconds.trim().parse::<u64>() {
+                        return Duration::from_secs(seconds);
+                    }
+                }
+
+                if directive.eq_ignore_ascii_case("no-store")
+                    || directive.eq_ignore_ascii_case("no-cache")
+                {
+                    return Duration::ZERO;
+                }
+            }
+        }
+    }
+
+    if let Some(expires) = headers.get(http::header::EXPIRES) {
+        if let Ok(expires) = expires.to_str() {
+            if let Ok(expires) = httpdate::parse_http_date(expires) {
+                if let Ok(remaining) = expires.duration_since(std::time::SystemTime::now()) {
+                    return remaining;
+                }
+
+                return Duration::ZERO;
+            }
+        }
+    }
+
+    default_ttl
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn waiter_is_woken_when_the_inflight_guard_completes() {
+        let cache = Arc::new(Cache::new(1024));
+
+        let guard = match lookup(cache.clone(), "hot-key").await {
+            Lookup::Miss(guard) => guard,
+            Lookup::Hit(_) => panic!("expected a miss on an empty cache"),
+        };
+
+        let waiter = {
+            let cache = cache.clone();
+
+            tokio::spawn(async move { lookup(cache, "hot-key").await })
+        };
+
+        // NOTE: Give the waiter task a chance to run until it is parked on the in-flight key's
+        // `Notify` before the guard completes, so the waiter only gets the entry if completion
+        // actually wakes it.
+        tokio::task::yield_now().await;
+
+        guard.complete(CacheEntry::new(
+            Bytes::from_static(b"segment bytes"),
+            None,
+            Duration::from_secs(60),
+        ));
+
+        let result = tokio::time::timeout(Duration::from_secs(1), waiter)
+            .await
+            .expect("waiter must be woken instead of hanging forever")
+            .unwrap();
+
+        match result {
+            Lookup::Hit(entry) => assert_eq!(entry.body, Bytes::from_static(b"segment bytes")),
+            Lookup::Miss(_) => panic!("expected the waiter to observe the completed entry"),
+        }
+    }
+}
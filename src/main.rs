@@ -1,4 +1,11 @@
-use std::{env, io::Error, net::IpAddr, str::FromStr, sync::Arc, time::Duration};
+use std::{
+    env,
+    io::Error,
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
 
 use tokio::net::{self, TcpListener};
 
@@ -6,16 +13,19 @@ use percent_encoding::{NON_ALPHANUMERIC, utf8_percent_encode};
 
 use serde::Deserialize;
 
+use bytes::Bytes;
+use futures_util::StreamExt;
+
 use tracing::{debug, error, info, level_filters::LevelFilter, trace, warn};
 use tracing_subscriber::EnvFilter;
 
-use http::{Method, Proxy, StatusCode, Url, header, redirect::Policy};
+use http::{Method, StatusCode, Url, header, redirect::Policy};
 
 use axum::{
     Router,
     body::Body,
-    extract::{Extension, Query},
-    http::{HeaderName, HeaderValue, Uri, uri::Scheme},
+    extract::{ConnectInfo, Extension, Query},
+    http::{HeaderMap, HeaderName, HeaderValue, Uri, uri::Scheme},
     response::{IntoResponse, Response},
     routing::get,
     serve,
@@ -32,6 +42,11 @@ use tower_http::{
 
 use rand::{Rng, distr::Alphanumeric, rng};
 
+mod cache;
+mod proxy_config;
+mod proxy_protocol;
+mod ratelimit;
+
 fn generate_key(len: usize) -> String {
     rng()
         .sample_iter(&Alphanumeric)
@@ -40,18 +55,193 @@ fn generate_key(len: usize) -> String {
         .collect::<String>()
 }
 
+// NOTE: Only playlists need to be buffered and rewritten; everything else (segments, binary
+// chunks) is streamed straight through to keep memory flat under many concurrent fetches.
+fn is_playlist_response(content_type: Option<&HeaderValue>, url: &str) -> bool {
+    if let Some(content_type) = content_type {
+        if let Ok(content_type) = content_type.to_str() {
+            let content_type = content_type.to_ascii_lowercase();
+
+            if content_type.contains("mpegurl") {
+                return true;
+            }
+        }
+    }
+
+    url.to_ascii_lowercase().ends_with(".m3u8")
+}
+
+// NOTE: Per RFC 9110 §7.6.1, hop-by-hop headers are meaningful only for a single transport-level
+// connection and must never be forwarded by an intermediary, mirroring Go's
+// `httputil.ReverseProxy`.
+const HOP_BY_HOP_HEADERS: &[HeaderName] = &[
+    header::CONNECTION,
+    header::PROXY_AUTHENTICATE,
+    header::PROXY_AUTHORIZATION,
+    header::TE,
+    header::TRAILER,
+    header::TRANSFER_ENCODING,
+    header::UPGRADE,
+];
+
+/// Removes hop-by-hop headers from `headers`, including any extra header names the upstream
+/// listed in its own `Connection` header.
+fn strip_hop_by_hop_headers(headers: &mut HeaderMap) {
+    if let Some(connection) = headers.get(header::CONNECTION) {
+        if let Ok(connection) = connection.to_str() {
+            let extra: Vec<HeaderName> = connection
+                .split(',')
+                .filter_map(|name| HeaderName::from_bytes(name.trim().as_bytes()).ok())
+                .collect();
+
+            for name in extra {
+                headers.remove(name);
+            }
+        }
+    }
+
+    for name in HOP_BY_HOP_HEADERS {
+        headers.remove(name);
+    }
+
+    headers.remove(HeaderName::from_static("keep-alive"));
+}
+
+/// Copies the upstream headers that are safe to forward to the client onto `builder`, skipping
+/// hop-by-hop headers and any header name already present in `already_set`.
+fn passthrough_response_headers(
+    mut builder: axum::http::response::Builder,
+    upstream_headers: &HeaderMap,
+    already_set: &[HeaderName],
+) -> axum::http::response::Builder {
+    let mut headers = upstream_headers.clone();
+    strip_hop_by_hop_headers(&mut headers);
+
+    for (name, value) in headers.iter() {
+        if already_set.contains(name) {
+            continue;
+        }
+
+        builder = builder.header(name, value);
+    }
+
+    builder
+}
+
+/// Builds the outbound `X-Forwarded-For` value, appending `client` to whatever the inbound
+/// request already carried.
+fn build_x_forwarded_for(request_headers: &HeaderMap, client: IpAddr) -> HeaderValue {
+    match request_headers
+        .get("x-forwarded-for")
+        .and_then(|value| value.to_str().ok())
+    {
+        Some(existing) if !existing.is_empty() => {
+            HeaderValue::from_str(&format!("{}, {}", existing, client))
+                .unwrap_or_else(|_| HeaderValue::from_str(&client.to_string()).unwrap())
+        }
+        _ => HeaderValue::from_str(&client.to_string()).unwrap(),
+    }
+}
+
+/// Wraps a proxied response stream so that it's also accumulated and stored in the cache once it
+/// finishes successfully and within budget.
+///
+/// The upstream `inner` stream is drained by a spawned task at its own pace -- not the client's.
+/// This matters for the single-flight de-duplication in `cache::lookup`: other requests for the
+/// same URL wait on this fetch completing, and without this decoupling they'd be stuck for as
+/// long as the *first* client takes to receive its copy of the response, e.g. for the entire
+/// duration of a `MUUUXY_RATE_LIMIT_BPS`-throttled download.
+fn stream_and_cache(
+    mut inner: impl futures_util::Stream<Item = Result<Bytes, std::io::Error>> + Unpin + Send + 'static,
+    max_len: usize,
+    guard: Option<cache::FetchGuard>,
+    content_type: Option<HeaderValue>,
+    ttl: Duration,
+) -> impl futures_util::Stream<Item = Result<Bytes, std::io::Error>> {
+    let (tx, rx) = tokio::sync::mpsc::unbounded_channel::<Result<Bytes, std::io::Error>>();
+
+    tokio::spawn(async move {
+        let mut streamed = 0usize;
+        let mut buffer = Vec::new();
+        let mut overflowed = false;
+
+        while let Some(chunk) = inner.next().await {
+            match chunk {
+                Ok(chunk) => {
+                    streamed += chunk.len();
+
+                    if streamed > max_len {
+                        let _ = tx.send(Err(std::io::Error::other(
+                            "content length of proxied request is great than max allowed",
+                        )));
+
+                        return;
+                    }
+
+                    if !overflowed {
+                        if buffer.len() + chunk.len() <= max_len {
+                            buffer.extend_from_slice(&chunk);
+                        } else {
+                            overflowed = true;
+                        }
+                    }
+
+                    // NOTE: An error here just means every client receiver dropped; keep draining
+                    // upstream regardless so the cache still gets populated for the next request.
+                    let _ = tx.send(Ok(chunk));
+                }
+                Err(e) => {
+                    let _ = tx.send(Err(e));
+
+                    return;
+                }
+            }
+        }
+
+        if !overflowed {
+            if let Some(guard) = guard {
+                guard.complete(cache::CacheEntry::new(
+                    Bytes::from(buffer),
+                    content_type,
+                    ttl,
+                ));
+            }
+        }
+    });
+
+    futures_util::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|item| (item, rx))
+    })
+}
+
+/// Wraps `stream` in the outbound bandwidth throttle when `bps` is configured, boxing either way
+/// so both streaming paths share a single return type.
+fn maybe_throttle(
+    stream: impl futures_util::Stream<Item = Result<Bytes, std::io::Error>> + Send + 'static,
+    bps: Option<u64>,
+) -> std::pin::Pin<Box<dyn futures_util::Stream<Item = Result<Bytes, std::io::Error>> + Send>> {
+    match bps {
+        Some(bps) if bps > 0 => Box::pin(ratelimit::throttle(Box::pin(stream), bps)),
+        _ => Box::pin(stream),
+    }
+}
+
 const DEFAULT_MUUUXY_SERVER_SCHEME: &str = "http";
 const DEFAULT_MUUUXY_SERVER_HOST: &str = "0.0.0.0";
 const DEFAULT_MUUUXY_SERVER_PORT: &str = "3000";
 const DEFAULT_MUUUXY_SERVER_DOMAIN: &str = "localhost:3000";
+const DEFAULT_MUUUXY_CACHE_MAX_BYTES: usize = 256 * 1_000_000;
 
 pub struct State {
     scheme: String,
     host: String,
     port: String,
     domain: String,
-    proxy: Option<String>,
+    proxy: proxy_config::ProxyConfig,
     key: String,
+    cache: Arc<cache::Cache>,
+    rate_limiter: Option<Arc<ratelimit::RateLimiter>>,
+    rate_limit_bps: Option<u64>,
 }
 
 impl State {
@@ -60,8 +250,11 @@ impl State {
         host: String,
         port: String,
         domain: String,
-        proxy: Option<String>,
+        proxy: proxy_config::ProxyConfig,
         key: String,
+        cache: Arc<cache::Cache>,
+        rate_limiter: Option<Arc<ratelimit::RateLimiter>>,
+        rate_limit_bps: Option<u64>,
     ) -> Self {
         Self {
             scheme,
@@ -70,6 +263,9 @@ impl State {
             domain,
             proxy,
             key,
+            cache,
+            rate_limiter,
+            rate_limit_bps,
         }
     }
 }
@@ -89,11 +285,35 @@ struct ProxyParams {
     key: String,
 }
 
-async fn proxy(params: Query<ProxyParams>, state: Extension<Arc<State>>) -> impl IntoResponse {
+async fn proxy(
+    params: Query<ProxyParams>,
+    request_headers: HeaderMap,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    state: Extension<Arc<State>>,
+) -> impl IntoResponse {
     let params: ProxyParams = params.0;
 
     let response_builder = Response::builder();
 
+    if let Some(limiter) = &state.rate_limiter {
+        // NOTE: Keyed on the resolved socket peer only -- `ConnectInfo` already reflects the real
+        // client when PROXY protocol is enabled (see `proxy_protocol`), and otherwise is the raw
+        // TCP peer. `X-Forwarded-For` is client-controlled and unauthenticated, so it is
+        // deliberately never trusted for a security control like rate limiting.
+        if let Err(retry_after) = limiter.check(client_addr.ip()) {
+            warn!(client = client_addr.ip().to_string(), "rate limit exceeded");
+
+            return response_builder
+                .status(StatusCode::TOO_MANY_REQUESTS)
+                .header(
+                    header::RETRY_AFTER,
+                    HeaderValue::from_str(&retry_after.as_secs().max(1).to_string()).unwrap(),
+                )
+                .body(Body::from("rate limit exceeded"))
+                .unwrap();
+        }
+    }
+
     if params.key != state.key {
         return response_builder
             .status(StatusCode::UNAUTHORIZED)
@@ -193,6 +413,33 @@ async fn proxy(params: Query<ProxyParams>, state: Extension<Arc<State>>) -> impl
         };
     }
 
+    // NOTE: A client range request gets served straight from upstream and never the cache, since
+    // the cache only ever stores a full representation of the resource.
+    let range = request_headers.get(header::RANGE).cloned();
+
+    let fetch_guard = if range.is_none() {
+        match cache::lookup(state.cache.clone(), &url_to_proxy).await {
+            cache::Lookup::Hit(entry) => {
+                debug!(url = url_to_proxy, "cache hit");
+
+                return response_builder
+                    .status(StatusCode::OK)
+                    .header(
+                        header::CONTENT_TYPE,
+                        entry.content_type.clone().unwrap_or_else(|| {
+                            HeaderValue::from_static("application/octet-stream")
+                        }),
+                    )
+                    .header(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"))
+                    .body(Body::from(entry.body))
+                    .unwrap();
+            }
+            cache::Lookup::Miss(guard) => Some(guard),
+        }
+    } else {
+        None
+    };
+
     const HTTP_BODY_MAX_LENGTH: usize = 50 * 1_000_000;
     const HTTP_CONNECTION_TIMEOUT: Duration = Duration::from_secs(5);
     const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
@@ -211,17 +458,23 @@ async fn proxy(params: Query<ProxyParams>, state: Extension<Arc<State>>) -> impl
         .https_only(true)
         .user_agent(HTTP_USER_AGENT);
 
-    builder = if let Some(proxy) = &state.proxy {
-        debug!(proxy = proxy, "using proxy on proxied url");
+    if !state.proxy.is_empty() {
+        debug!("using configured upstream proxy on proxied url");
 
-        builder.proxy(Proxy::all(proxy).unwrap())
-    } else {
-        builder
-    };
+        builder = state.proxy.apply(builder);
+    }
 
     let client = builder.build().unwrap();
 
-    let response = match client.get(&url_to_proxy).send().await {
+    let mut upstream_request = client.get(&url_to_proxy).header(
+        "X-Forwarded-For",
+        build_x_forwarded_for(&request_headers, client_addr.ip()),
+    );
+    if let Some(range) = &range {
+        upstream_request = upstream_request.header(header::RANGE, range.clone());
+    }
+
+    let response = match upstream_request.send().await {
         Ok(r) => r,
         Err(e) => {
             error!("failed to perform request on the proxied url: {}", e);
@@ -233,23 +486,118 @@ async fn proxy(params: Query<ProxyParams>, state: Extension<Arc<State>>) -> impl
         }
     };
 
-    if response.status() != StatusCode::OK {
+    let status = response.status();
+    if status != StatusCode::OK && status != StatusCode::PARTIAL_CONTENT {
         error!(
             url = url_to_proxy,
-            status = response.status().to_string(),
-            "response from proxied server returned with a non 200 code"
+            status = status.to_string(),
+            "response from proxied server returned with a non 200/206 code"
         );
 
         return response_builder
             .status(StatusCode::INTERNAL_SERVER_ERROR)
             .body(Body::from(
-                "request to proxied server returned a non 200 status",
+                "request to proxied server returned a non 200/206 status",
             ))
             .unwrap();
     }
 
     let headers = response.headers().clone();
 
+    if !is_playlist_response(headers.get(header::CONTENT_TYPE), &url_to_proxy) {
+        trace!("binary chunk got, streaming it through");
+
+        if status == StatusCode::PARTIAL_CONTENT {
+            trace!("upstream answered with a partial content range");
+
+            let mut partial_response = response_builder
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(
+                    header::CONTENT_TYPE,
+                    headers
+                        .get(header::CONTENT_TYPE)
+                        .cloned()
+                        .unwrap_or_else(|| HeaderValue::from_static("application/octet-stream")),
+                )
+                .header(
+                    header::ACCEPT_RANGES,
+                    headers
+                        .get(header::ACCEPT_RANGES)
+                        .cloned()
+                        .unwrap_or_else(|| HeaderValue::from_static("bytes")),
+                );
+
+            if let Some(content_range) = headers.get(header::CONTENT_RANGE) {
+                partial_response = partial_response.header(header::CONTENT_RANGE, content_range);
+            }
+
+            if let Some(content_length) = headers.get(header::CONTENT_LENGTH) {
+                partial_response =
+                    partial_response.header(header::CONTENT_LENGTH, content_length);
+            }
+
+            partial_response = passthrough_response_headers(
+                partial_response,
+                &headers,
+                &[
+                    header::CONTENT_TYPE,
+                    header::ACCEPT_RANGES,
+                    header::CONTENT_RANGE,
+                    header::CONTENT_LENGTH,
+                ],
+            );
+
+            let mut streamed = 0usize;
+            let body_stream = response.bytes_stream().map(move |chunk| {
+                let chunk = chunk.map_err(std::io::Error::other)?;
+
+                streamed += chunk.len();
+                if streamed > HTTP_BODY_MAX_LENGTH {
+                    return Err(std::io::Error::other(
+                        "content length of proxied request is great than max allowed",
+                    ));
+                }
+
+                Ok::<Bytes, std::io::Error>(chunk)
+            });
+            let body_stream = maybe_throttle(body_stream, state.rate_limit_bps);
+
+            return partial_response
+                .body(Body::from_stream(body_stream))
+                .unwrap();
+        }
+
+        let ttl = cache::ttl_from_headers(&headers, cache::DEFAULT_SEGMENT_TTL);
+        let body_stream = stream_and_cache(
+            response
+                .bytes_stream()
+                .map(|chunk| chunk.map_err(std::io::Error::other)),
+            HTTP_BODY_MAX_LENGTH,
+            fetch_guard,
+            Some(HeaderValue::from_static("application/octet-stream")),
+            ttl,
+        );
+        let body_stream = maybe_throttle(body_stream, state.rate_limit_bps);
+
+        let mut streamed_response = response_builder
+            .status(StatusCode::OK)
+            .header(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("application/octet-stream"),
+            )
+            .header(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+
+        streamed_response = passthrough_response_headers(
+            streamed_response,
+            &headers,
+            &[header::CONTENT_TYPE, header::ACCEPT_RANGES],
+        );
+
+        return streamed_response
+            .body(Body::from_stream(body_stream))
+            .unwrap();
+    }
+
     let body = response.bytes().await.unwrap();
     if body.len() > HTTP_BODY_MAX_LENGTH {
         info!(
@@ -269,11 +617,19 @@ async fn proxy(params: Query<ProxyParams>, state: Extension<Arc<State>>) -> impl
     let playlist = match m3u8::parse_playlist(&body) {
         Ok((_, playlist)) => playlist,
         _ => {
-            // NOTE: When the data isn't a playlist, we are considering it a binary chunk. We have
-            // to check if it is right.
+            // NOTE: The content-type/extension looked like a playlist but it didn't parse as
+            // one. We have to consider it a binary chunk at this point.
             let len = body.len();
             let len_as_string = len.to_string();
 
+            if let Some(fetch_guard) = fetch_guard {
+                fetch_guard.complete(cache::CacheEntry::new(
+                    body.clone(),
+                    Some(HeaderValue::from_static("application/octet-stream")),
+                    cache::ttl_from_headers(&headers, cache::DEFAULT_SEGMENT_TTL),
+                ));
+            }
+
             return response_builder
                 .status(StatusCode::OK)
                 .header(
@@ -348,15 +704,27 @@ async fn proxy(params: Query<ProxyParams>, state: Extension<Arc<State>>) -> impl
                     .unwrap();
             };
 
-            return response_builder
+            if let Some(fetch_guard) = fetch_guard {
+                fetch_guard.complete(cache::CacheEntry::new(
+                    Bytes::from(master_buffer.clone()),
+                    Some(content_type.clone()),
+                    cache::ttl_from_headers(&headers, cache::DEFAULT_PLAYLIST_TTL),
+                ));
+            }
+
+            let mut master_response = response_builder
                 .status(StatusCode::OK)
                 .header(header::CONTENT_TYPE, content_type)
                 .header(
                     header::CONTENT_LENGTH,
                     HeaderValue::from_str(&len_as_string).unwrap(),
-                )
-                .body(Body::from(master_buffer))
-                .unwrap();
+                );
+
+            if let Some(cache_control) = headers.get(header::CACHE_CONTROL) {
+                master_response = master_response.header(header::CACHE_CONTROL, cache_control);
+            }
+
+            return master_response.body(Body::from(master_buffer)).unwrap();
         }
         m3u8::Playlist::MediaPlaylist(mut media) => {
             trace!("media playlist got");
@@ -415,15 +783,27 @@ async fn proxy(params: Query<ProxyParams>, state: Extension<Arc<State>>) -> impl
                     .unwrap();
             };
 
-            return response_builder
+            if let Some(fetch_guard) = fetch_guard {
+                fetch_guard.complete(cache::CacheEntry::new(
+                    Bytes::from(media_buffer.clone()),
+                    Some(content_type.clone()),
+                    cache::ttl_from_headers(&headers, cache::DEFAULT_PLAYLIST_TTL),
+                ));
+            }
+
+            let mut media_response = response_builder
                 .status(StatusCode::OK)
                 .header(header::CONTENT_TYPE, content_type)
                 .header(
                     header::CONTENT_LENGTH,
                     HeaderValue::from_str(&len_as_string).unwrap(),
-                )
-                .body(Body::from(media_buffer))
-                .unwrap();
+                );
+
+            if let Some(cache_control) = headers.get(header::CACHE_CONTROL) {
+                media_response = media_response.header(header::CACHE_CONTROL, cache_control);
+            }
+
+            return media_response.body(Body::from(media_buffer)).unwrap();
         }
     };
 }
@@ -490,17 +870,22 @@ async fn main() -> Result<(), Error> {
         }
     };
 
-    let server_proxy = match env::var("MUUUXY_SERVER_PROXY") {
-        Ok(addr) => {
-            info!(proxy = addr, "using proxy on proxied requests");
+    let server_proxy = proxy_config::ProxyConfig::from_env();
+    if server_proxy.is_empty() {
+        warn!("MUUUXY_SERVER_PROXY/MUUUXY_SERVER_PROXY_HTTPS not set, proxying requests directly");
+    } else {
+        info!("using configured upstream proxy on proxied requests");
+    }
 
-            Some(addr)
-        }
-        Err(_) => {
-            warn!("MUUUXY_SERVER_PROXY not set, using default",);
+    let proxy_protocol_enabled = match env::var("MUUUXY_PROXY_PROTOCOL") {
+        Ok(value) if value.eq_ignore_ascii_case("on") => {
+            info!(
+                "MUUUXY_PROXY_PROTOCOL=on, expecting a PROXY protocol header on every connection"
+            );
 
-            None
+            true
         }
+        _ => false,
     };
 
     const GENERATE_KEY_LENGTH: usize = 32;
@@ -518,6 +903,87 @@ async fn main() -> Result<(), Error> {
 
     info!(key = server_key, "server key defined");
 
+    let cache_max_bytes = match env::var("MUUUXY_CACHE_MAX_BYTES") {
+        Ok(value) => match value.parse::<usize>() {
+            Ok(value) => value,
+            Err(_) => {
+                warn!(
+                    value = value,
+                    "MUUUXY_CACHE_MAX_BYTES isn't a valid number, using default"
+                );
+
+                DEFAULT_MUUUXY_CACHE_MAX_BYTES
+            }
+        },
+        Err(_) => {
+            warn!(
+                value = DEFAULT_MUUUXY_CACHE_MAX_BYTES,
+                "MUUUXY_CACHE_MAX_BYTES not set, using default",
+            );
+
+            DEFAULT_MUUUXY_CACHE_MAX_BYTES
+        }
+    };
+
+    let rate_limiter = match env::var("MUUUXY_RATE_LIMIT_RPS") {
+        Ok(value) => match value.parse::<f64>() {
+            Ok(rps) if rps > 0.0 => {
+                info!(rps = rps, "per-client request rate limiting enabled");
+
+                Some(Arc::new(ratelimit::RateLimiter::new(rps)))
+            }
+            _ => {
+                warn!(
+                    value = value,
+                    "MUUUXY_RATE_LIMIT_RPS isn't a valid positive number, rate limiting disabled"
+                );
+
+                None
+            }
+        },
+        Err(_) => {
+            warn!("MUUUXY_RATE_LIMIT_RPS not set, rate limiting disabled");
+
+            None
+        }
+    };
+
+    if let Some(rate_limiter) = rate_limiter.clone() {
+        const RATE_LIMIT_EVICT_INTERVAL: Duration = Duration::from_secs(60);
+        const RATE_LIMIT_IDLE_AFTER: Duration = Duration::from_secs(300);
+
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(RATE_LIMIT_EVICT_INTERVAL).await;
+
+                rate_limiter.evict_idle(RATE_LIMIT_IDLE_AFTER);
+            }
+        });
+    }
+
+    let rate_limit_bps = match env::var("MUUUXY_RATE_LIMIT_BPS") {
+        Ok(value) => match value.parse::<u64>() {
+            Ok(bps) if bps > 0 => {
+                info!(bps = bps, "per-client bandwidth throttling enabled");
+
+                Some(bps)
+            }
+            _ => {
+                warn!(
+                    value = value,
+                    "MUUUXY_RATE_LIMIT_BPS isn't a valid positive number, bandwidth throttling disabled"
+                );
+
+                None
+            }
+        },
+        Err(_) => {
+            warn!("MUUUXY_RATE_LIMIT_BPS not set, bandwidth throttling disabled");
+
+            None
+        }
+    };
+
     let server_address = format!("{}:{}", server_host, server_port);
 
     let state = Arc::new(State::new(
@@ -527,6 +993,9 @@ async fn main() -> Result<(), Error> {
         server_domain,
         server_proxy,
         server_key,
+        Arc::new(cache::Cache::new(cache_max_bytes)),
+        rate_limiter,
+        rate_limit_bps,
     ));
 
     let service = ServiceBuilder::new()
@@ -550,5 +1019,14 @@ async fn main() -> Result<(), Error> {
 
     info!("muuuxy server started");
 
-    return serve(TcpListener::bind(server_address).await?, router).await;
+    let listener = proxy_protocol::MaybeProxyProtocolListener::new(
+        TcpListener::bind(server_address).await?,
+        proxy_protocol_enabled,
+    )?;
+
+    return serve(
+        listener,
+        router.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await;
 }
@@ -0,0 +1,128 @@
+//! Structured, authenticated upstream proxy configuration, with distinct HTTP/HTTPS proxies and
+//! a no-proxy bypass list, parsed once from the environment in `main`.
+
+use std::env;
+
+use http::{ClientBuilder, NoProxy, Proxy};
+
+#[derive(Clone)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+impl Credentials {
+    fn parse(raw: &str) -> Option<Self> {
+        let (username, password) = raw.split_once(':')?;
+
+        Some(Self {
+            username: username.to_string(),
+            password: password.to_string(),
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct Endpoint {
+    pub url: String,
+    pub credentials: Option<Credentials>,
+}
+
+pub struct ProxyConfig {
+    http: Option<Endpoint>,
+    https: Option<Endpoint>,
+    no_proxy: Option<String>,
+}
+
+impl ProxyConfig {
+    /// Reads `MUUUXY_SERVER_PROXY` (HTTP upstream), `MUUUXY_SERVER_PROXY_HTTPS` (HTTPS
+    /// upstream), `MUUUXY_SERVER_PROXY_AUTH` (shared `username:password` credentials) and
+    /// `MUUUXY_SERVER_NO_PROXY` (bypass hostnames) into a single config.
+    pub fn from_env() -> Self {
+        let credentials = env::var("MUUUXY_SERVER_PROXY_AUTH")
+            .ok()
+            .and_then(|raw| Credentials::parse(&raw));
+
+        let http = env::var("MUUUXY_SERVER_PROXY").ok().map(|url| Endpoint {
+            url,
+            credentials: credentials.clone(),
+        });
+
+        let https = env::var("MUUUXY_SERVER_PROXY_HTTPS")
+            .ok()
+            .map(|url| Endpoint {
+                url,
+                credentials: credentials.clone(),
+            });
+
+        let no_proxy = env::var("MUUUXY_SERVER_NO_PROXY").ok();
+
+        Self {
+            http,
+            https,
+            no_proxy,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.http.is_none() && self.https.is_none()
+    }
+
+    /// Installs the configured HTTP/HTTPS upstream proxies onto `builder`.
+    pub fn apply(&self, mut builder: ClientBuilder) -> ClientBuilder {
+        match (&self.http, &self.https) {
+            (Some(endpoint), None) => {
+                // NOTE: No HTTPS-specific proxy was configured, so the HTTP endpoint covers all
+                // schemes. The proxied client always fetches over HTTPS (`https_only(true)`), so
+                // treating `MUUUXY_SERVER_PROXY` as HTTP-only here would silently never be used.
+                match build_proxy(endpoint, self.no_proxy.as_deref(), ProxyScheme::All) {
+                    Ok(proxy) => builder = builder.proxy(proxy),
+                    Err(e) => tracing::error!("invalid MUUUXY_SERVER_PROXY: {}", e),
+                }
+            }
+            (http, https) => {
+                if let Some(endpoint) = http {
+                    match build_proxy(endpoint, self.no_proxy.as_deref(), ProxyScheme::Http) {
+                        Ok(proxy) => builder = builder.proxy(proxy),
+                        Err(e) => tracing::error!("invalid MUUUXY_SERVER_PROXY: {}", e),
+                    }
+                }
+
+                if let Some(endpoint) = https {
+                    match build_proxy(endpoint, self.no_proxy.as_deref(), ProxyScheme::Https) {
+                        Ok(proxy) => builder = builder.proxy(proxy),
+                        Err(e) => tracing::error!("invalid MUUUXY_SERVER_PROXY_HTTPS: {}", e),
+                    }
+                }
+            }
+        }
+
+        builder
+    }
+}
+
+enum ProxyScheme {
+    Http,
+    Https,
+    All,
+}
+
+fn build_proxy(
+    endpoint: &Endpoint,
+    no_proxy: Option<&str>,
+    scheme: ProxyScheme,
+) -> Result<Proxy, http::Error> {
+    let mut proxy = match scheme {
+        ProxyScheme::Http => Proxy::http(&endpoint.url)?,
+        ProxyScheme::Https => Proxy::https(&endpoint.url)?,
+        ProxyScheme::All => Proxy::all(&endpoint.url)?,
+    };
+
+    if let Some(credentials) = &endpoint.credentials {
+        proxy = proxy.basic_auth(&credentials.username, &credentials.password);
+    }
+
+    proxy = proxy.no_proxy(no_proxy.and_then(NoProxy::from_string));
+
+    Ok(proxy)
+}
@@ -0,0 +1,382 @@
+//! PROXY protocol v1/v2 support so the real client address survives an L4 load balancer or TCP
+//! terminator sitting in front of muuuxy, instead of every connection looking like it came from
+//! the balancer.
+
+use std::{
+    io,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+    time::{Instant, timeout_at},
+};
+
+use axum::serve::Listener;
+
+use tracing::{error, warn};
+
+const V2_SIGNATURE: [u8; 12] = [
+    0x0D, 0x0A, 0x0D, 0x0A, 0x00, 0x0D, 0x0A, 0x51, 0x55, 0x49, 0x54, 0x0A,
+];
+/// Per spec, a v1 header line is at most 107 bytes including the trailing `\r\n`.
+const V1_MAX_LINE_LENGTH: usize = 107;
+/// Bounds how long we'll wait for a connection's PROXY protocol header to fully arrive before
+/// giving up on it. Since sniffing now happens off the accept loop (see
+/// `MaybeProxyProtocolListener`), this only ever holds up the one slow connection, not every
+/// other client trying to connect.
+const HEADER_SNIFF_TIMEOUT: Duration = Duration::from_secs(5);
+/// How many sniffed connections can queue up waiting for `accept()` to pick them up.
+const ACCEPT_QUEUE_DEPTH: usize = 1024;
+
+/// The client address a PROXY protocol header resolved to, or `Local` when the header explicitly
+/// said the connection carries no address of its own (e.g. a load balancer health check).
+enum ProxiedAddr {
+    Remote(SocketAddr),
+    Local,
+}
+
+/// Reads the PROXY protocol header off the front of `stream`, returning the original client
+/// address it carries. The header bytes are consumed; everything after them is left untouched
+/// for the caller to hand off to the real protocol (HTTP).
+async fn read_header(stream: &mut TcpStream) -> io::Result<ProxiedAddr> {
+    let deadline = Instant::now() + HEADER_SNIFF_TIMEOUT;
+
+    let mut prefix = [0u8; 12];
+    let filled = timeout_at(deadline, read_fully(stream, &mut prefix)).await??;
+
+    if filled < prefix.len() {
+        return Err(io::Error::other(
+            "connection closed before a full PROXY protocol header arrived",
+        ));
+    }
+
+    if prefix == V2_SIGNATURE {
+        timeout_at(deadline, read_v2(stream)).await?
+    } else if &prefix[..5] == b"PROXY" {
+        timeout_at(deadline, read_v1(stream, &prefix))
+            .await?
+            .map(ProxiedAddr::Remote)
+    } else {
+        Err(io::Error::other("missing PROXY protocol header"))
+    }
+}
+
+/// Reads into `buf` until it's completely filled or the peer closes the connection. Unlike
+/// peeking with a fixed-size buffer -- which returns immediately with however many bytes happen
+/// to be buffered so far and would busy-loop if called back-to-back on a connection that trickles
+/// data in -- each `read` here genuinely blocks on I/O readiness.
+async fn read_fully(stream: &mut TcpStream, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+
+    while filled < buf.len() {
+        let n = stream.read(&mut buf[filled..]).await?;
+
+        if n == 0 {
+            break;
+        }
+
+        filled += n;
+    }
+
+    Ok(filled)
+}
+
+async fn read_v1(stream: &mut TcpStream, prefix: &[u8]) -> io::Result<SocketAddr> {
+    let mut line = prefix.to_vec();
+
+    if !line.ends_with(b"\r\n") {
+        let mut byte = [0u8; 1];
+
+        loop {
+            stream.read_exact(&mut byte).await?;
+            line.push(byte[0]);
+
+            if line.ends_with(b"\r\n") {
+                break;
+            }
+
+            if line.len() > V1_MAX_LINE_LENGTH {
+                return Err(io::Error::other("PROXY protocol v1 header too long"));
+            }
+        }
+    }
+
+    let line = String::from_utf8(line)
+        .map_err(|_| io::Error::other("invalid PROXY protocol v1 header"))?;
+    let mut fields = line.trim_end().split_whitespace();
+
+    match fields.next() {
+        Some("PROXY") => {}
+        _ => return Err(io::Error::other("missing PROXY protocol v1 signature")),
+    }
+
+    let _protocol = fields
+        .next()
+        .ok_or_else(|| io::Error::other("missing PROXY protocol v1 protocol field"))?;
+
+    let source_ip = fields
+        .next()
+        .ok_or_else(|| io::Error::other("missing PROXY protocol v1 source address"))?;
+
+    let _dest_ip = fields.next();
+
+    let source_port = fields
+        .next()
+        .ok_or_else(|| io::Error::other("missing PROXY protocol v1 source port"))?;
+
+    let ip = source_ip
+        .parse()
+        .map_err(|_| io::Error::other("invalid PROXY protocol v1 source address"))?;
+    let port: u16 = source_port
+        .parse()
+        .map_err(|_| io::Error::other("invalid PROXY protocol v1 source port"))?;
+
+    Ok(SocketAddr::new(ip, port))
+}
+
+async fn read_v2(stream: &mut TcpStream) -> io::Result<ProxiedAddr> {
+    let mut rest = [0u8; 4];
+    stream.read_exact(&mut rest).await?;
+
+    let length = u16::from_be_bytes([rest[2], rest[3]]) as usize;
+
+    let mut payload = vec![0u8; length];
+    stream.read_exact(&mut payload).await?;
+
+    parse_v2(&rest, &payload)
+}
+
+/// Parses the version/command byte, family/protocol byte and address payload of a PROXY protocol
+/// v2 header (everything after the 12-byte signature). Kept separate from the socket I/O above so
+/// it can be exercised with plain byte fixtures.
+fn parse_v2(rest: &[u8; 4], payload: &[u8]) -> io::Result<ProxiedAddr> {
+    let version_command = rest[0];
+    if version_command >> 4 != 2 {
+        return Err(io::Error::other("unsupported PROXY protocol version"));
+    }
+
+    let command = version_command & 0x0F;
+    if command == 0x00 {
+        // NOTE: LOCAL means the connection was established by the proxy itself (e.g. a health
+        // check) and carries no client address of its own. Per spec the receiver should use the
+        // connection's real addresses in this case, not reject it -- rejecting would break health
+        // checks through a PROXY-protocol-speaking balancer.
+        return Ok(ProxiedAddr::Local);
+    }
+
+    let family_protocol = rest[1];
+
+    match family_protocol >> 4 {
+        0x1 => {
+            if payload.len() < 12 {
+                return Err(io::Error::other("truncated PROXY protocol v2 IPv4 address"));
+            }
+
+            let ip = Ipv4Addr::new(payload[0], payload[1], payload[2], payload[3]);
+            let port = u16::from_be_bytes([payload[8], payload[9]]);
+
+            Ok(ProxiedAddr::Remote(SocketAddr::new(ip.into(), port)))
+        }
+        0x2 => {
+            if payload.len() < 36 {
+                return Err(io::Error::other("truncated PROXY protocol v2 IPv6 address"));
+            }
+
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(&payload[..16]);
+            let ip = Ipv6Addr::from(octets);
+            let port = u16::from_be_bytes([payload[32], payload[33]]);
+
+            Ok(ProxiedAddr::Remote(SocketAddr::new(ip.into(), port)))
+        }
+        _ => Err(io::Error::other(
+            "unsupported PROXY protocol v2 address family",
+        )),
+    }
+}
+
+/// Accepts raw TCP connections off `listener` forever, handing each one to its own task to sniff
+/// the PROXY protocol header. This keeps one slow/trickling peer from blocking every other
+/// client's accept -- the accept loop itself never awaits the header read.
+async fn sniff_loop(listener: TcpListener, tx: mpsc::Sender<(TcpStream, SocketAddr)>) {
+    loop {
+        let (mut stream, peer_addr) = match listener.accept().await {
+            Ok(pair) => pair,
+            Err(e) => {
+                error!("failed to accept TCP connection: {}", e);
+
+                continue;
+            }
+        };
+
+        let tx = tx.clone();
+
+        tokio::spawn(async move {
+            match read_header(&mut stream).await {
+                Ok(ProxiedAddr::Remote(client_addr)) => {
+                    let _ = tx.send((stream, client_addr)).await;
+                }
+                Ok(ProxiedAddr::Local) => {
+                    let _ = tx.send((stream, peer_addr)).await;
+                }
+                Err(e) => {
+                    warn!(
+                        peer = %peer_addr,
+                        error = %e,
+                        "rejecting connection missing a valid PROXY protocol header"
+                    );
+
+                    let _ = stream.shutdown().await;
+                }
+            }
+        });
+    }
+}
+
+/// Wraps a `TcpListener` so that, when enabled, every accepted connection is expected to start
+/// with a PROXY protocol header; connections missing a valid one are rejected. Header sniffing
+/// runs off the accept loop (see `sniff_loop`) so a single slow connection can't stall acceptance
+/// of every other client.
+pub struct MaybeProxyProtocolListener {
+    local_addr: SocketAddr,
+    inner: Option<TcpListener>,
+    sniffed: Option<mpsc::Receiver<(TcpStream, SocketAddr)>>,
+}
+
+impl MaybeProxyProtocolListener {
+    pub fn new(inner: TcpListener, enabled: bool) -> io::Result<Self> {
+        let local_addr = inner.local_addr()?;
+
+        if !enabled {
+            return Ok(Self {
+                local_addr,
+                inner: Some(inner),
+                sniffed: None,
+            });
+        }
+
+        let (tx, rx) = mpsc::channel(ACCEPT_QUEUE_DEPTH);
+
+        tokio::spawn(sniff_loop(inner, tx));
+
+        Ok(Self {
+            local_addr,
+            inner: None,
+            sniffed: Some(rx),
+        })
+    }
+}
+
+impl Listener for MaybeProxyProtocolListener {
+    type Io = TcpStream;
+    type Addr = SocketAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        if let Some(inner) = &self.inner {
+            loop {
+                match inner.accept().await {
+                    Ok(pair) => return pair,
+                    Err(e) => {
+                        error!("failed to accept TCP connection: {}", e);
+
+                        continue;
+                    }
+                }
+            }
+        }
+
+        self.sniffed
+            .as_mut()
+            .expect("listener is either direct or sniffing")
+            .recv()
+            .await
+            .expect("sniff_loop task must not exit while the listener is alive")
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        Ok(self.local_addr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v2_header(command: u8, family_protocol: u8, payload: &[u8]) -> ([u8; 4], Vec<u8>) {
+        let rest = [
+            0x20 | command,
+            family_protocol,
+            ((payload.len() >> 8) & 0xFF) as u8,
+            (payload.len() & 0xFF) as u8,
+        ];
+
+        (rest, payload.to_vec())
+    }
+
+    #[test]
+    fn parses_v2_ipv4() {
+        let mut payload = Vec::new();
+        payload.extend_from_slice(&[10, 0, 0, 1]);
+        payload.extend_from_slice(&[203, 0, 113, 7]);
+        payload.extend_from_slice(&54321u16.to_be_bytes());
+        payload.extend_from_slice(&443u16.to_be_bytes());
+
+        let (rest, payload) = v2_header(0x1, 0x11, &payload);
+
+        match parse_v2(&rest, &payload).unwrap() {
+            ProxiedAddr::Remote(addr) => {
+                assert_eq!(addr, SocketAddr::from(([10, 0, 0, 1], 54321)));
+            }
+            ProxiedAddr::Local => panic!("expected a remote address"),
+        }
+    }
+
+    #[test]
+    fn parses_v2_ipv6() {
+        let src = Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1);
+        let dst = Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 2);
+
+        let mut payload = Vec::new();
+        payload.extend_from_slice(&src.octets());
+        payload.extend_from_slice(&dst.octets());
+        payload.extend_from_slice(&51234u16.to_be_bytes());
+        payload.extend_from_slice(&443u16.to_be_bytes());
+
+        let (rest, payload) = v2_header(0x1, 0x21, &payload);
+
+        match parse_v2(&rest, &payload).unwrap() {
+            ProxiedAddr::Remote(addr) => {
+                assert_eq!(addr, SocketAddr::from((src, 51234)));
+            }
+            ProxiedAddr::Local => panic!("expected a remote address"),
+        }
+    }
+
+    #[test]
+    fn local_command_falls_back_instead_of_erroring() {
+        let (rest, payload) = v2_header(0x0, 0x11, &[]);
+
+        assert!(matches!(
+            parse_v2(&rest, &payload).unwrap(),
+            ProxiedAddr::Local
+        ));
+    }
+
+    #[test]
+    fn rejects_unsupported_version() {
+        let rest = [0x10, 0x11, 0x00, 0x00];
+
+        assert!(parse_v2(&rest, &[]).is_err());
+    }
+
+    #[test]
+    fn rejects_truncated_ipv4_payload() {
+        let (rest, payload) = v2_header(0x1, 0x11, &[1, 2, 3]);
+
+        assert!(parse_v2(&rest, &payload).is_err());
+    }
+}
@@ -0,0 +1,156 @@
+//! Per-client token-bucket rate limiting and outbound bandwidth throttling, so a single viewer
+//! can't hammer origin with requests or saturate the proxy's upstream bandwidth.
+
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+use futures_util::StreamExt;
+
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+    last_used: Instant,
+}
+
+/// A token bucket per client IP, refilled at `rps` tokens/sec up to a burst of `rps` tokens
+/// (never less than one, so an `rps` below 1 still admits a request).
+pub struct RateLimiter {
+    rps: f64,
+    burst: f64,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(rps: f64) -> Self {
+        Self {
+            rps,
+            burst: rps.max(1.0),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Tries to take one token for `client`. Returns `Ok(())` when allowed, or `Err(retry_after)`
+    /// with how long the caller should wait before the bucket has a token again.
+    pub fn check(&self, client: IpAddr) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+
+        let bucket = buckets.entry(client).or_insert_with(|| Bucket {
+            tokens: self.burst,
+            last_refill: now,
+            last_used: now,
+        });
+
+        let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rps).min(self.burst);
+        bucket.last_refill = now;
+        bucket.last_used = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+
+            Ok(())
+        } else {
+            let deficit = 1.0 - bucket.tokens;
+
+            Err(Duration::from_secs_f64(deficit / self.rps))
+        }
+    }
+
+    /// Drops buckets idle for longer than `idle_after`. Meant to be called periodically so
+    /// long-running servers don't accumulate one bucket per IP forever.
+    pub fn evict_idle(&self, idle_after: Duration) {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+
+        buckets.retain(|_, bucket| now.duration_since(bucket.last_used) < idle_after);
+    }
+}
+
+/// Wraps `inner` so each chunk is delayed to keep the stream's average rate at or below
+/// `bytes_per_sec`.
+pub fn throttle(
+    inner: impl futures_util::Stream<Item = Result<Bytes, std::io::Error>> + Unpin + Send + 'static,
+    bytes_per_sec: u64,
+) -> impl futures_util::Stream<Item = Result<Bytes, std::io::Error>> {
+    futures_util::stream::unfold(inner, move |mut inner| async move {
+        match inner.next().await {
+            Some(Ok(chunk)) => {
+                let delay = Duration::from_secs_f64(chunk.len() as f64 / bytes_per_sec as f64);
+                if !delay.is_zero() {
+                    tokio::time::sleep(delay).await;
+                }
+
+                Some((Ok(chunk), inner))
+            }
+            Some(Err(e)) => Some((Err(e), inner)),
+            None => None,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::thread::sleep;
+
+    fn client(n: u8) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(127, 0, 0, n))
+    }
+
+    #[test]
+    fn allows_up_to_the_burst_then_denies() {
+        let limiter = RateLimiter::new(2.0);
+        let client = client(1);
+
+        assert!(limiter.check(client).is_ok());
+        assert!(limiter.check(client).is_ok());
+        assert!(limiter.check(client).is_err());
+    }
+
+    #[test]
+    fn refills_over_time() {
+        let limiter = RateLimiter::new(10.0);
+        let client = client(2);
+
+        for _ in 0..10 {
+            assert!(limiter.check(client).is_ok());
+        }
+        assert!(limiter.check(client).is_err());
+
+        // NOTE: At 10 tokens/sec, waiting 200ms should refill roughly 2 tokens.
+        sleep(Duration::from_millis(200));
+
+        assert!(limiter.check(client).is_ok());
+        assert!(limiter.check(client).is_ok());
+        assert!(limiter.check(client).is_err());
+    }
+
+    #[test]
+    fn buckets_are_independent_per_client() {
+        let limiter = RateLimiter::new(1.0);
+
+        assert!(limiter.check(client(3)).is_ok());
+        assert!(limiter.check(client(3)).is_err());
+        assert!(limiter.check(client(4)).is_ok());
+    }
+
+    #[test]
+    fn evict_idle_drops_stale_buckets_only() {
+        let limiter = RateLimiter::new(1.0);
+
+        limiter.check(client(5)).unwrap();
+        sleep(Duration::from_millis(50));
+        limiter.check(client(6)).unwrap();
+
+        limiter.evict_idle(Duration::from_millis(25));
+
+        assert_eq!(limiter.buckets.lock().unwrap().len(), 1);
+    }
+}